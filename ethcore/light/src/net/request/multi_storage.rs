@@ -0,0 +1,212 @@
+// Copyright 2015-2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Ethereum.
+
+// Parity Ethereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Ethereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity Ethereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Batched multi-key storage proof requests, for proving several slots of
+//! the same account in a single round trip.
+
+use super::{Field, NoSuchOutput, OutputKind, Output};
+use ethereum_types::H256;
+use bytes::Bytes;
+
+/// Potentially incomplete request for a batch of storage proofs belonging
+/// to a single account.
+#[derive(Debug, Clone, PartialEq, Eq, RlpDecodable)]
+pub struct IncompleteRequest {
+	/// Block hash to request state proof for.
+	pub block_hash: Field<H256>,
+	/// Hash of the account's address.
+	pub address_hash: Field<H256>,
+	/// Hashes of the storage keys to prove.
+	pub key_hashes: Vec<Field<H256>>,
+}
+
+// `rlp_derive`'s `RlpEncodable` can't cope with a `Vec<Field<H256>>` field
+// (it mangles the element type's own generics when emitting `append_list`),
+// so `key_hashes` needs a hand-written encoding here.
+impl rlp::Encodable for IncompleteRequest {
+	fn rlp_append(&self, stream: &mut rlp::RlpStream) {
+		stream.begin_list(3);
+		stream.append(&self.block_hash);
+		stream.append(&self.address_hash);
+		stream.append_list::<Field<H256>, _>(&self.key_hashes);
+	}
+}
+
+impl super::IncompleteRequest for IncompleteRequest {
+	type Complete = CompleteRequest;
+	type Response = Response;
+
+	fn check_outputs<F>(&self, mut f: F) -> Result<(), NoSuchOutput>
+	where F: FnMut(usize, usize, OutputKind) -> Result<(), NoSuchOutput>
+	{
+		if let Field::BackReference(req, idx) = self.block_hash {
+			f(req, idx, OutputKind::Hash)?
+		}
+
+		if let Field::BackReference(req, idx) = self.address_hash {
+			f(req, idx, OutputKind::Hash)?
+		}
+
+		for key_hash in &self.key_hashes {
+			if let Field::BackReference(req, idx) = *key_hash {
+				f(req, idx, OutputKind::Hash)?
+			}
+		}
+
+		Ok(())
+	}
+
+	fn note_outputs<F>(&self, mut f: F) where F: FnMut(usize, OutputKind) {
+		for i in 0..self.key_hashes.len() {
+			f(i, OutputKind::Hash);
+		}
+	}
+
+	fn fill<F>(&mut self, oracle: F) where F: Fn(usize, usize) -> Result<Output, NoSuchOutput> {
+		if let Field::BackReference(req, idx) = self.block_hash {
+			self.block_hash = match oracle(req, idx) {
+				Ok(Output::Hash(block_hash)) => Field::Scalar(block_hash),
+				_ => Field::BackReference(req, idx),
+			}
+		}
+
+		if let Field::BackReference(req, idx) = self.address_hash {
+			self.address_hash = match oracle(req, idx) {
+				Ok(Output::Hash(address_hash)) => Field::Scalar(address_hash),
+				_ => Field::BackReference(req, idx),
+			}
+		}
+
+		for key_hash in self.key_hashes.iter_mut() {
+			if let Field::BackReference(req, idx) = *key_hash {
+				*key_hash = match oracle(req, idx) {
+					Ok(Output::Hash(key_hash)) => Field::Scalar(key_hash),
+					_ => Field::BackReference(req, idx),
+				}
+			}
+		}
+	}
+
+	fn complete(self) -> Result<Self::Complete, NoSuchOutput> {
+		Ok(CompleteRequest {
+			block_hash: self.block_hash.into_scalar()?,
+			address_hash: self.address_hash.into_scalar()?,
+			key_hashes: self.key_hashes.into_iter()
+				.map(|key_hash| key_hash.into_scalar())
+				.collect::<Result<_, _>>()?,
+		})
+	}
+
+	fn adjust_refs<F>(&mut self, mut mapping: F) where F: FnMut(usize) -> usize {
+		self.block_hash.adjust_req(&mut mapping);
+		self.address_hash.adjust_req(&mut mapping);
+
+		for key_hash in self.key_hashes.iter_mut() {
+			key_hash.adjust_req(&mut mapping);
+		}
+	}
+}
+
+/// A complete request for a batch of storage proofs belonging to a single
+/// account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompleteRequest {
+	/// Block hash to request state proof for.
+	pub block_hash: H256,
+	/// Hash of the account's address.
+	pub address_hash: H256,
+	/// Hashes of the storage keys to prove.
+	pub key_hashes: Vec<H256>,
+}
+
+/// The output of a request for a batch of storage proofs.
+///
+/// `values[i]` is only meaningful as the proof for `key_hashes[i]` of the
+/// originating request once [`Response::validate_length`] has confirmed the
+/// two vectors are the same length; callers must check this before trusting
+/// the index-for-index correspondence (e.g. before calling `fill_outputs`).
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Response {
+	/// Inclusion/exclusion proof: the account branch plus the union of the
+	/// storage-trie nodes touched by every key in the request.
+	pub proof: Vec<Bytes>,
+	/// Storage values, in the same order as the request's `key_hashes`.
+	pub values: Vec<H256>,
+}
+
+impl Response {
+	/// Check that this response returned exactly one value per requested key
+	/// hash, so that `values[i]` can be trusted to correspond to
+	/// `request.key_hashes[i]`. A peer returning a short or padded `values`
+	/// vector fails this check and must be rejected before its outputs are
+	/// used.
+	pub fn validate_length(&self, request: &CompleteRequest) -> bool {
+		self.values.len() == request.key_hashes.len()
+	}
+}
+
+impl super::ResponseLike for Response {
+	/// Fill reusable outputs by providing them to the function.
+	///
+	/// Only valid to call once [`Response::validate_length`] has passed for
+	/// the originating request; this method has no access to `key_hashes`
+	/// and cannot check the correspondence itself.
+	fn fill_outputs<F>(&self, mut f: F) where F: FnMut(usize, Output) {
+		for (i, value) in self.values.iter().enumerate() {
+			f(i, Output::Hash(*value));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{IncompleteRequest, CompleteRequest, Response, Field};
+	use ethereum_types::H256;
+
+	// Pins the hand-rolled `Encodable` impl against the derived `Decodable`
+	// for `key_hashes: Vec<Field<H256>>`, the field the derive can't handle.
+	#[test]
+	fn encode_decode_round_trip() {
+		let request = IncompleteRequest {
+			block_hash: Field::Scalar(H256::from_low_u64_be(1)),
+			address_hash: Field::Scalar(H256::from_low_u64_be(2)),
+			key_hashes: vec![
+				Field::Scalar(H256::from_low_u64_be(3)),
+				Field::Scalar(H256::from_low_u64_be(4)),
+			],
+		};
+
+		let encoded = rlp::encode(&request);
+		let decoded: IncompleteRequest = rlp::decode(&encoded).expect("round trip decode");
+
+		assert_eq!(decoded, request);
+	}
+
+	#[test]
+	fn validate_length_checks_values_match_key_hashes() {
+		let request = CompleteRequest {
+			block_hash: H256::from_low_u64_be(1),
+			address_hash: H256::from_low_u64_be(2),
+			key_hashes: vec![H256::from_low_u64_be(3), H256::from_low_u64_be(4)],
+		};
+
+		let matching = Response { proof: Vec::new(), values: vec![H256::zero(), H256::zero()] };
+		assert!(matching.validate_length(&request));
+
+		let short = Response { proof: Vec::new(), values: vec![H256::zero()] };
+		assert!(!short.validate_length(&request));
+	}
+}
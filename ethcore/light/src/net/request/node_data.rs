@@ -0,0 +1,116 @@
+// Copyright 2015-2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Ethereum.
+
+// Parity Ethereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Ethereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity Ethereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Raw trie-node requests (`GetNodeData`-style).
+
+use super::{Field, NoSuchOutput, OutputKind, Output};
+use ethereum_types::H256;
+use bytes::Bytes;
+use keccak_hash::keccak;
+
+/// Potentially incomplete request for a raw trie node.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct IncompleteRequest {
+	/// Hash of the trie node to fetch.
+	pub node_hash: Field<H256>,
+}
+
+impl super::IncompleteRequest for IncompleteRequest {
+	type Complete = CompleteRequest;
+	type Response = Response;
+
+	fn check_outputs<F>(&self, mut f: F) -> Result<(), NoSuchOutput>
+	where F: FnMut(usize, usize, OutputKind) -> Result<(), NoSuchOutput>
+	{
+		if let Field::BackReference(req, idx) = self.node_hash {
+			f(req, idx, OutputKind::Hash)?
+		}
+
+		Ok(())
+	}
+
+	fn note_outputs<F>(&self, mut f: F) where F: FnMut(usize, OutputKind) {
+		f(0, OutputKind::Hash);
+	}
+
+	fn fill<F>(&mut self, oracle: F) where F: Fn(usize, usize) -> Result<Output, NoSuchOutput> {
+		if let Field::BackReference(req, idx) = self.node_hash {
+			self.node_hash = match oracle(req, idx) {
+				Ok(Output::Hash(node_hash)) => Field::Scalar(node_hash),
+				_ => Field::BackReference(req, idx),
+			}
+		}
+	}
+
+	fn complete(self) -> Result<Self::Complete, NoSuchOutput> {
+		Ok(CompleteRequest {
+			node_hash: self.node_hash.into_scalar()?,
+		})
+	}
+
+	fn adjust_refs<F>(&mut self, mut mapping: F) where F: FnMut(usize) -> usize {
+		self.node_hash.adjust_req(&mut mapping);
+	}
+}
+
+/// A complete request for a raw trie node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompleteRequest {
+	/// Hash of the trie node to fetch.
+	pub node_hash: H256,
+}
+
+/// The output of a request for a raw trie node.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Response {
+	/// The raw, RLP-encoded node data.
+	pub data: Bytes,
+}
+
+impl Response {
+	/// Check that the returned data actually hashes to the requested node hash.
+	pub fn check_hash(&self, node_hash: H256) -> bool {
+		keccak(&self.data) == node_hash
+	}
+}
+
+impl super::ResponseLike for Response {
+	/// Fill reusable outputs by providing them to the function.
+	fn fill_outputs<F>(&self, mut f: F) where F: FnMut(usize, Output) {
+		f(0, Output::Hash(keccak(&self.data)));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Response;
+	use keccak_hash::keccak;
+
+	#[test]
+	fn check_hash_accepts_matching_data() {
+		let data = b"arbitrary trie node payload".to_vec();
+		let response = Response { data: data.clone() };
+
+		assert!(response.check_hash(keccak(&data)));
+	}
+
+	#[test]
+	fn check_hash_rejects_mismatched_data() {
+		let response = Response { data: b"some node".to_vec() };
+
+		assert!(!response.check_hash(keccak(b"a different node")));
+	}
+}
@@ -19,6 +19,9 @@
 use super::{Field, NoSuchOutput, OutputKind, Output};
 use ethereum_types::H256;
 use bytes::Bytes;
+use keccak_hash::keccak;
+use rlp::Rlp;
+use std::fmt;
 
 /// Potentially incomplete request for an storage proof.
 #[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
@@ -120,4 +123,396 @@ impl super::ResponseLike for Response {
 	fn fill_outputs<F>(&self, mut f: F) where F: FnMut(usize, Output) {
 		f(0, Output::Hash(self.value));
 	}
+}
+
+/// Error in verifying a storage proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+	/// A node referenced by hash wasn't present among the supplied proof nodes.
+	MissingNode(H256),
+	/// A proof node's RLP was malformed, or didn't have the shape of a
+	/// branch, extension, or leaf node.
+	BadNode,
+	/// The proof contained nodes not reachable while walking the key path.
+	ExtraNode,
+	/// An inclusion proof decoded to a value different from the claimed one.
+	WrongValue,
+}
+
+impl fmt::Display for ProofError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ProofError::MissingNode(hash) => write!(f, "missing trie node: {}", hash),
+			ProofError::BadNode => write!(f, "malformed trie node in proof"),
+			ProofError::ExtraNode => write!(f, "proof contains unused nodes"),
+			ProofError::WrongValue => write!(f, "proof decodes to a different value than claimed"),
+		}
+	}
+}
+
+impl ::std::error::Error for ProofError {}
+
+// The nibbles (half-bytes) making up a key path, most significant first.
+fn key_nibbles(key_hash: &H256) -> Vec<u8> {
+	let mut nibbles = Vec::with_capacity(64);
+	for byte in key_hash.as_bytes() {
+		nibbles.push(byte >> 4);
+		nibbles.push(byte & 0x0f);
+	}
+	nibbles
+}
+
+// Decode a hex-prefix encoded path (leaf/extension first RLP item) into its
+// nibbles and whether it denotes a leaf node.
+fn hex_prefix_decode(encoded: &[u8]) -> Result<(Vec<u8>, bool), ProofError> {
+	if encoded.is_empty() {
+		return Err(ProofError::BadNode);
+	}
+
+	let first = encoded[0];
+	let is_leaf = first & 0x20 != 0;
+	let is_odd = first & 0x10 != 0;
+
+	let mut nibbles = Vec::with_capacity(encoded.len() * 2);
+	if is_odd {
+		nibbles.push(first & 0x0f);
+	}
+	for byte in &encoded[1..] {
+		nibbles.push(byte >> 4);
+		nibbles.push(byte & 0x0f);
+	}
+
+	Ok((nibbles, is_leaf))
+}
+
+// Resolve a branch/extension child reference: either a 32-byte hash that
+// must be looked up among the proof nodes, or (for subtries small enough to
+// encode inline) the embedded node RLP itself.
+enum Child<'a> {
+	/// Hash of a node present elsewhere in the proof.
+	Hash(H256),
+	/// Node RLP embedded directly in the parent.
+	Inline(Rlp<'a>),
+	/// No child at this position.
+	Empty,
+}
+
+fn decode_child<'a>(rlp: &'a Rlp<'a>) -> Result<Child<'a>, ProofError> {
+	if rlp.is_empty() {
+		return Ok(Child::Empty);
+	}
+
+	if rlp.is_data() {
+		let data = rlp.data().map_err(|_| ProofError::BadNode)?;
+		if data.is_empty() {
+			return Ok(Child::Empty);
+		}
+		if data.len() == 32 {
+			return Ok(Child::Hash(H256::from_slice(data)));
+		}
+		return Err(ProofError::BadNode);
+	}
+
+	Ok(Child::Inline(rlp.clone()))
+}
+
+impl Response {
+	/// Self-verify this proof against a known storage root and key hash.
+	///
+	/// Walks the supplied Merkle-Patricia nodes starting at `storage_root`,
+	/// consuming nibbles of `key_hash` one node at a time and checking at
+	/// each step that `keccak256(node) == expected_hash`. Returns
+	/// `Ok(Some(value))` for a verified inclusion proof, or `Ok(None)` for a
+	/// verified *exclusion* proof (the caller must then treat the value as
+	/// zero). Returns `Err` if the proof is internally inconsistent, leaves
+	/// unused nodes, or claims an inclusion value other than `self.value`.
+	pub fn verify(&self, storage_root: H256, key_hash: H256) -> Result<Option<H256>, ProofError> {
+		let nibbles = key_nibbles(&key_hash);
+		let mut used = vec![false; self.proof.len()];
+
+		let result = self.walk(storage_root, &nibbles, 0, &mut used)?;
+
+		if used.iter().any(|&u| !u) {
+			return Err(ProofError::ExtraNode);
+		}
+
+		match result {
+			Some(value) if value != self.value => Err(ProofError::WrongValue),
+			other => Ok(other),
+		}
+	}
+
+	// Walk the proof starting from `expected_hash`, having already consumed
+	// `nibbles[..depth]` of the key path.
+	fn walk(&self, expected_hash: H256, nibbles: &[u8], depth: usize, used: &mut [bool]) -> Result<Option<H256>, ProofError> {
+		let idx = self.proof.iter().position(|node| keccak(node) == expected_hash)
+			.ok_or(ProofError::MissingNode(expected_hash))?;
+		used[idx] = true;
+
+		self.walk_node(Rlp::new(&self.proof[idx]), nibbles, depth, used)
+	}
+
+	// Walk an already-resolved node (either looked up by hash or embedded
+	// inline in its parent).
+	fn walk_node(&self, node: Rlp, nibbles: &[u8], depth: usize, used: &mut [bool]) -> Result<Option<H256>, ProofError> {
+		match node.item_count().map_err(|_| ProofError::BadNode)? {
+			17 => {
+				if depth == nibbles.len() {
+					let value = node.at(16).map_err(|_| ProofError::BadNode)?;
+					if value.is_empty() {
+						return Ok(None);
+					}
+					return Ok(Some(rlp_to_h256(&value)?));
+				}
+
+				let branch = node.at(nibbles[depth] as usize).map_err(|_| ProofError::BadNode)?;
+				self.follow(branch, nibbles, depth + 1, used)
+			}
+			2 => {
+				let path_rlp = node.at(0).map_err(|_| ProofError::BadNode)?;
+				let path_bytes = path_rlp.data().map_err(|_| ProofError::BadNode)?;
+				let (path, is_leaf) = hex_prefix_decode(path_bytes)?;
+
+				if nibbles[depth..].len() < path.len() || nibbles[depth..depth + path.len()] != path[..] {
+					return Ok(None);
+				}
+
+				let new_depth = depth + path.len();
+				let value_rlp = node.at(1).map_err(|_| ProofError::BadNode)?;
+
+				if is_leaf {
+					if new_depth != nibbles.len() {
+						return Ok(None);
+					}
+					Ok(Some(rlp_to_h256(&value_rlp)?))
+				} else {
+					self.follow(value_rlp, nibbles, new_depth, used)
+				}
+			}
+			_ => Err(ProofError::BadNode),
+		}
+	}
+
+	// Follow a branch/extension child, which may be a hash reference
+	// (requiring a fresh lookup in the proof) or an inline embedded node.
+	fn follow(&self, child: Rlp, nibbles: &[u8], depth: usize, used: &mut [bool]) -> Result<Option<H256>, ProofError> {
+		match decode_child(&child)? {
+			Child::Empty => Ok(None),
+			Child::Hash(hash) => self.walk(hash, nibbles, depth, used),
+			Child::Inline(rlp) => self.walk_node(rlp, nibbles, depth, used),
+		}
+	}
+}
+
+fn rlp_to_h256(rlp: &Rlp) -> Result<H256, ProofError> {
+	let data = rlp.data().map_err(|_| ProofError::BadNode)?;
+	if data.len() > 32 {
+		return Err(ProofError::BadNode);
+	}
+
+	let mut buf = [0u8; 32];
+	buf[32 - data.len()..].copy_from_slice(data);
+	Ok(H256::from(buf))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Response, ProofError, keccak};
+	use ethereum_types::H256;
+	use rlp::RlpStream;
+
+	// Test-only mirror of `hex_prefix_decode`, used to build proof fixtures.
+	fn hex_prefix_encode(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+		let odd = nibbles.len() % 2 == 1;
+		let mut out = Vec::with_capacity(nibbles.len() / 2 + 1);
+
+		let mut first = if is_leaf { 0x20 } else { 0x00 };
+		let mut idx = 0;
+		if odd {
+			first |= 0x10 | nibbles[0];
+			idx = 1;
+		}
+		out.push(first);
+
+		while idx < nibbles.len() {
+			out.push((nibbles[idx] << 4) | nibbles[idx + 1]);
+			idx += 2;
+		}
+
+		out
+	}
+
+	fn trimmed(value: &H256) -> Vec<u8> {
+		let bytes = value.as_bytes();
+		match bytes.iter().position(|&b| b != 0) {
+			Some(i) => bytes[i..].to_vec(),
+			None => Vec::new(),
+		}
+	}
+
+	fn leaf_node(suffix: &[u8], value: H256) -> Vec<u8> {
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&hex_prefix_encode(suffix, true));
+		stream.append(&trimmed(&value));
+		stream.out().to_vec()
+	}
+
+	fn extension_node(prefix: &[u8], child: &[u8]) -> Vec<u8> {
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&hex_prefix_encode(prefix, false));
+		append_child(&mut stream, child);
+		stream.out().to_vec()
+	}
+
+	fn branch_node(children: &[Option<Vec<u8>>]) -> Vec<u8> {
+		assert_eq!(children.len(), 16);
+		let mut stream = RlpStream::new_list(17);
+		for child in children {
+			match child {
+				Some(node) => append_child(&mut stream, node),
+				None => { stream.append_empty_data(); }
+			}
+		}
+		stream.append_empty_data();
+		stream.out().to_vec()
+	}
+
+	fn append_child(stream: &mut RlpStream, child: &[u8]) {
+		if child.len() < 32 {
+			stream.append_raw(child, 1);
+		} else {
+			stream.append(&keccak(child));
+		}
+	}
+
+	fn no_children() -> Vec<Option<Vec<u8>>> {
+		vec![None; 16]
+	}
+
+	// key_hash with nibbles [3, 0, 0, ..., 0] (64 nibbles).
+	fn test_key() -> H256 {
+		let mut bytes = [0u8; 32];
+		bytes[0] = 0x30;
+		H256::from(bytes)
+	}
+
+	#[test]
+	fn verify_inclusion_via_branch_and_leaf() {
+		let key = test_key();
+		let value = H256::from_low_u64_be(42);
+
+		let leaf = leaf_node(&[0u8; 63], value);
+		let mut children = no_children();
+		children[3] = Some(leaf.clone());
+		let root = branch_node(&children);
+
+		let response = Response { proof: vec![root.clone(), leaf], value };
+		let root_hash = keccak(&root);
+
+		assert_eq!(response.verify(root_hash, key), Ok(Some(value)));
+	}
+
+	#[test]
+	fn verify_exclusion_via_empty_branch_slot() {
+		let key = test_key();
+
+		let children = no_children();
+		let root = branch_node(&children);
+		let root_hash = keccak(&root);
+
+		let response = Response { proof: vec![root], value: H256::zero() };
+		assert_eq!(response.verify(root_hash, key), Ok(None));
+	}
+
+	#[test]
+	fn verify_exclusion_via_diverging_extension_path() {
+		let key = test_key(); // first nibble is 3
+
+		// Extension path starts with nibble 5, diverging from the key's
+		// leading nibble 3 -- the key cannot be under this subtree.
+		let root = extension_node(&[5, 5], H256::zero().as_bytes());
+		let root_hash = keccak(&root);
+
+		let response = Response { proof: vec![root], value: H256::zero() };
+		assert_eq!(response.verify(root_hash, key), Ok(None));
+	}
+
+	#[test]
+	fn verify_rejects_wrong_value() {
+		let key = test_key();
+		let value = H256::from_low_u64_be(42);
+		let claimed = H256::from_low_u64_be(43);
+
+		let leaf = leaf_node(&[0u8; 63], value);
+		let mut children = no_children();
+		children[3] = Some(leaf.clone());
+		let root = branch_node(&children);
+		let root_hash = keccak(&root);
+
+		let response = Response { proof: vec![root, leaf], value: claimed };
+		assert_eq!(response.verify(root_hash, key), Err(ProofError::WrongValue));
+	}
+
+	#[test]
+	fn verify_rejects_extra_node() {
+		let key = test_key();
+		let value = H256::from_low_u64_be(42);
+
+		let leaf = leaf_node(&[0u8; 63], value);
+		let mut children = no_children();
+		children[3] = Some(leaf.clone());
+		let root = branch_node(&children);
+		let root_hash = keccak(&root);
+
+		let unrelated = leaf_node(&[1u8; 63], H256::from_low_u64_be(7));
+		let response = Response { proof: vec![root, leaf, unrelated], value };
+		assert_eq!(response.verify(root_hash, key), Err(ProofError::ExtraNode));
+	}
+
+	#[test]
+	fn verify_rejects_missing_node() {
+		let key = test_key();
+		let value = H256::from_low_u64_be(42);
+
+		let leaf = leaf_node(&[0u8; 63], value);
+		let mut children = no_children();
+		children[3] = Some(leaf);
+		let root = branch_node(&children);
+		let root_hash = keccak(&root);
+
+		// Leaf is omitted from the proof, even though the branch references it by hash.
+		let response = Response { proof: vec![root], value };
+		match response.verify(root_hash, key) {
+			Err(ProofError::MissingNode(_)) => {}
+			other => panic!("expected MissingNode, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn verify_inclusion_via_inline_embedded_children() {
+		// key_hash nibbles: 63 zeros followed by a final nibble of 7.
+		let mut bytes = [0u8; 32];
+		bytes[31] = 0x07;
+		let key = H256::from(bytes);
+		let value = H256::zero();
+
+		// Innermost leaf: empty remaining path, small enough to embed inline.
+		let leaf = leaf_node(&[], value);
+		assert!(leaf.len() < 32);
+
+		// Middle branch: only child is the inline leaf at nibble 7; also small
+		// enough to embed inline in its parent.
+		let mut children = no_children();
+		children[7] = Some(leaf);
+		let branch = branch_node(&children);
+		assert!(branch.len() < 32);
+
+		// Root: extension over the first 63 nibbles (all zero), pointing at
+		// the inline-embedded branch.
+		let root = extension_node(&[0u8; 63], &branch);
+		let root_hash = keccak(&root);
+
+		let response = Response { proof: vec![root], value };
+		assert_eq!(response.verify(root_hash, key), Ok(Some(value)));
+	}
 }
\ No newline at end of file
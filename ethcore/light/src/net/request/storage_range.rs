@@ -0,0 +1,831 @@
+// Copyright 2015-2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Ethereum.
+
+// Parity Ethereum is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Ethereum is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity Ethereum.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Range proofs for a contiguous span of storage slots, for snap-style bulk
+//! state streaming. Lets a light client download a contract's storage
+//! without knowing the keys in advance, by proving a bounded span
+//! `[start_hash, limit_hash]` in a single message.
+
+use super::{Field, NoSuchOutput, OutputKind, Output};
+use ethereum_types::H256;
+use bytes::Bytes;
+use keccak_hash::keccak;
+use rlp::{Rlp, RlpStream};
+use std::fmt;
+
+/// Potentially incomplete request for a range of storage slots.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct IncompleteRequest {
+	/// Block hash to request state proof for.
+	pub block_hash: Field<H256>,
+	/// Hash of the account's address.
+	pub address_hash: Field<H256>,
+	/// Lower bound (inclusive) of the storage key range.
+	pub start_hash: H256,
+	/// Upper bound (inclusive) of the storage key range.
+	pub limit_hash: H256,
+	/// Maximum number of entries to return.
+	pub max_entries: u64,
+}
+
+impl super::IncompleteRequest for IncompleteRequest {
+	type Complete = CompleteRequest;
+	type Response = Response;
+
+	fn check_outputs<F>(&self, mut f: F) -> Result<(), NoSuchOutput>
+	where F: FnMut(usize, usize, OutputKind) -> Result<(), NoSuchOutput>
+	{
+		if let Field::BackReference(req, idx) = self.block_hash {
+			f(req, idx, OutputKind::Hash)?
+		}
+
+		if let Field::BackReference(req, idx) = self.address_hash {
+			f(req, idx, OutputKind::Hash)?
+		}
+
+		Ok(())
+	}
+
+	fn note_outputs<F>(&self, mut f: F) where F: FnMut(usize, OutputKind) {
+		// index 0: the chain anchor (`Response::next_hash`), always provided
+		// by the responder so a follow-up range request can resume past
+		// `limit_hash` even when this response proved an empty range.
+		f(0, OutputKind::Hash);
+	}
+
+	fn fill<F>(&mut self, oracle: F) where F: Fn(usize, usize) -> Result<Output, NoSuchOutput> {
+		if let Field::BackReference(req, idx) = self.block_hash {
+			self.block_hash = match oracle(req, idx) {
+				Ok(Output::Hash(block_hash)) => Field::Scalar(block_hash),
+				_ => Field::BackReference(req, idx),
+			}
+		}
+
+		if let Field::BackReference(req, idx) = self.address_hash {
+			self.address_hash = match oracle(req, idx) {
+				Ok(Output::Hash(address_hash)) => Field::Scalar(address_hash),
+				_ => Field::BackReference(req, idx),
+			}
+		}
+	}
+
+	fn complete(self) -> Result<Self::Complete, NoSuchOutput> {
+		Ok(CompleteRequest {
+			block_hash: self.block_hash.into_scalar()?,
+			address_hash: self.address_hash.into_scalar()?,
+			start_hash: self.start_hash,
+			limit_hash: self.limit_hash,
+			max_entries: self.max_entries,
+		})
+	}
+
+	fn adjust_refs<F>(&mut self, mut mapping: F) where F: FnMut(usize) -> usize {
+		self.block_hash.adjust_req(&mut mapping);
+		self.address_hash.adjust_req(&mut mapping);
+	}
+}
+
+/// A complete request for a range of storage slots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompleteRequest {
+	/// Block hash to request state proof for.
+	pub block_hash: H256,
+	/// Hash of the account's address.
+	pub address_hash: H256,
+	/// Lower bound (inclusive) of the storage key range.
+	pub start_hash: H256,
+	/// Upper bound (inclusive) of the storage key range.
+	pub limit_hash: H256,
+	/// Maximum number of entries to return.
+	pub max_entries: u64,
+}
+
+/// A single proved `(key_hash, value)` leaf within the returned range.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Entry {
+	/// Hash of the storage key.
+	pub key_hash: H256,
+	/// Storage value at that key.
+	pub value: H256,
+}
+
+/// The output of a request for a range of storage slots.
+#[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
+pub struct Response {
+	/// The ordered leaves found in `[start_hash, limit_hash]`, bounded by
+	/// `max_entries`.
+	pub entries: Vec<Entry>,
+	/// Merkle proof for the first returned entry, or for the exclusion of
+	/// `start_hash` when `entries` is empty. It does not show that no leaf
+	/// lies between `start_hash` and the first returned entry.
+	pub left_proof: Vec<Bytes>,
+	/// Merkle proof for the last returned entry, or for the exclusion of
+	/// `limit_hash` when `entries` is empty. It does not show that no leaf
+	/// lies between the last returned entry and `limit_hash`.
+	pub right_proof: Vec<Bytes>,
+	/// Hash to resume a follow-up range request from. Always populated by
+	/// the responder, even for a fully-excluded (empty `entries`) range, so
+	/// the chain output at index 0 is never left unresolved: the last
+	/// returned key's hash when `entries` is non-empty, or the request's
+	/// own `limit_hash` otherwise.
+	pub next_hash: H256,
+}
+
+impl super::ResponseLike for Response {
+	/// Fill reusable outputs by providing them to the function.
+	fn fill_outputs<F>(&self, mut f: F) where F: FnMut(usize, Output) {
+		f(0, Output::Hash(self.next_hash));
+	}
+}
+
+/// Error in verifying a storage range proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+	/// A node referenced by hash wasn't present among the supplied proof nodes.
+	MissingNode(H256),
+	/// A proof node's RLP was malformed, or didn't have the shape of a
+	/// branch, extension, or leaf node.
+	BadNode,
+	/// A boundary proof contained nodes not reachable while walking the key path.
+	ExtraNode,
+	/// `entries` is not strictly sorted by ascending `key_hash`, or a key
+	/// fell outside `[start_hash, limit_hash]`.
+	OutOfOrder,
+	/// The left boundary proof doesn't authenticate the first returned entry
+	/// (or, for an empty range, the exclusion of `start_hash`).
+	BadLeftBoundary,
+	/// The right boundary proof doesn't authenticate the last returned entry
+	/// (or, for an empty range, the exclusion of `limit_hash`).
+	BadRightBoundary,
+	/// A subtree between the boundary paths doesn't reconstruct to the hash
+	/// referenced in the shared ancestor branch, meaning a leaf in that span
+	/// was altered or omitted.
+	RangeGap,
+}
+
+impl fmt::Display for ProofError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			ProofError::MissingNode(hash) => write!(f, "missing trie node: {}", hash),
+			ProofError::BadNode => write!(f, "malformed trie node in proof"),
+			ProofError::ExtraNode => write!(f, "proof contains unused nodes"),
+			ProofError::OutOfOrder => write!(f, "entries are not sorted within the claimed range"),
+			ProofError::BadLeftBoundary => write!(f, "left boundary proof does not authenticate the range start"),
+			ProofError::BadRightBoundary => write!(f, "right boundary proof does not authenticate the range end"),
+			ProofError::RangeGap => write!(f, "a subtree between the boundary proofs does not match: a leaf may have been omitted"),
+		}
+	}
+}
+
+impl ::std::error::Error for ProofError {}
+
+fn key_nibbles(key_hash: &H256) -> Vec<u8> {
+	let mut nibbles = Vec::with_capacity(64);
+	for byte in key_hash.as_bytes() {
+		nibbles.push(byte >> 4);
+		nibbles.push(byte & 0x0f);
+	}
+	nibbles
+}
+
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+	a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
+}
+
+fn hex_prefix_decode(encoded: &[u8]) -> Result<(Vec<u8>, bool), ProofError> {
+	if encoded.is_empty() {
+		return Err(ProofError::BadNode);
+	}
+
+	let first = encoded[0];
+	let is_leaf = first & 0x20 != 0;
+	let is_odd = first & 0x10 != 0;
+
+	let mut nibbles = Vec::with_capacity(encoded.len() * 2);
+	if is_odd {
+		nibbles.push(first & 0x0f);
+	}
+	for byte in &encoded[1..] {
+		nibbles.push(byte >> 4);
+		nibbles.push(byte & 0x0f);
+	}
+
+	Ok((nibbles, is_leaf))
+}
+
+fn hex_prefix_encode(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+	let odd = nibbles.len() % 2 == 1;
+	let mut out = Vec::with_capacity(nibbles.len() / 2 + 1);
+
+	let mut first = if is_leaf { 0x20 } else { 0x00 };
+	let mut idx = 0;
+	if odd {
+		first |= 0x10 | nibbles[0];
+		idx = 1;
+	}
+	out.push(first);
+
+	while idx < nibbles.len() {
+		out.push((nibbles[idx] << 4) | nibbles[idx + 1]);
+		idx += 2;
+	}
+
+	out
+}
+
+// Trim the leading zero bytes off a 32-byte value, the way scalar values are
+// stored in the trie (an empty byte string represents zero).
+fn trimmed_value(value: &H256) -> &[u8] {
+	let bytes = value.as_bytes();
+	match bytes.iter().position(|&b| b != 0) {
+		Some(i) => &bytes[i..],
+		None => &[],
+	}
+}
+
+fn rlp_to_h256(rlp: &Rlp) -> Result<H256, ProofError> {
+	let data = rlp.data().map_err(|_| ProofError::BadNode)?;
+	if data.len() > 32 {
+		return Err(ProofError::BadNode);
+	}
+
+	let mut buf = [0u8; 32];
+	buf[32 - data.len()..].copy_from_slice(data);
+	Ok(H256::from(buf))
+}
+
+// A branch/extension child reference: either a hash of a node present
+// elsewhere in the proof, or (for small enough subtries) the embedded node
+// RLP itself.
+enum Child<'a> {
+	Hash(H256),
+	Inline(Rlp<'a>),
+	Empty,
+}
+
+fn decode_child<'a>(rlp: &'a Rlp<'a>) -> Result<Child<'a>, ProofError> {
+	if rlp.is_empty() {
+		return Ok(Child::Empty);
+	}
+
+	if rlp.is_data() {
+		let data = rlp.data().map_err(|_| ProofError::BadNode)?;
+		if data.is_empty() {
+			return Ok(Child::Empty);
+		}
+		if data.len() == 32 {
+			return Ok(Child::Hash(H256::from_slice(data)));
+		}
+		return Err(ProofError::BadNode);
+	}
+
+	Ok(Child::Inline(rlp.clone()))
+}
+
+// Append a child reference the way the real trie encoding does: inline if
+// the child's own RLP is short enough to embed, by hash otherwise.
+fn append_child_ref(stream: &mut RlpStream, child_rlp: &[u8]) {
+	if child_rlp.len() < 32 {
+		stream.append_raw(child_rlp, 1);
+	} else {
+		stream.append(&keccak(child_rlp));
+	}
+}
+
+/// One branch node stepped through while walking a boundary proof: the
+/// node's hash, the key nibbles leading to it, the key depth (in nibbles)
+/// at which it was encountered, and which child nibble the walk followed.
+#[derive(Debug, Clone)]
+struct BranchStep {
+	hash: H256,
+	prefix: Vec<u8>,
+	depth: usize,
+	nibble: u8,
+}
+
+/// The end state of walking a boundary proof down to its key.
+enum BoundaryResult {
+	Included(H256),
+	Excluded,
+}
+
+// Walk `proof` from `storage_root`, consuming nibbles of `target`, and
+// record every branch node stepped through (for later range-gap checking).
+fn walk_boundary(
+	proof: &[Bytes],
+	storage_root: H256,
+	target: &[u8],
+) -> Result<(BoundaryResult, Vec<BranchStep>), ProofError> {
+	let mut used = vec![false; proof.len()];
+	let mut branches = Vec::new();
+
+	let result = walk(proof, storage_root, target, 0, &mut used, &mut branches)?;
+
+	if used.iter().any(|&u| !u) {
+		return Err(ProofError::ExtraNode);
+	}
+
+	Ok((result, branches))
+}
+
+fn walk(
+	proof: &[Bytes],
+	expected_hash: H256,
+	nibbles: &[u8],
+	depth: usize,
+	used: &mut [bool],
+	branches: &mut Vec<BranchStep>,
+) -> Result<BoundaryResult, ProofError> {
+	let idx = proof.iter().position(|node| keccak(node) == expected_hash)
+		.ok_or(ProofError::MissingNode(expected_hash))?;
+	used[idx] = true;
+
+	walk_node(proof, Rlp::new(&proof[idx]), nibbles, depth, used, branches)
+}
+
+fn walk_node(
+	proof: &[Bytes],
+	node: Rlp,
+	nibbles: &[u8],
+	depth: usize,
+	used: &mut [bool],
+	branches: &mut Vec<BranchStep>,
+) -> Result<BoundaryResult, ProofError> {
+	match node.item_count().map_err(|_| ProofError::BadNode)? {
+		17 => {
+			if depth == nibbles.len() {
+				let value = node.at(16).map_err(|_| ProofError::BadNode)?;
+				return if value.is_empty() {
+					Ok(BoundaryResult::Excluded)
+				} else {
+					Ok(BoundaryResult::Included(rlp_to_h256(&value)?))
+				};
+			}
+
+			let nibble = nibbles[depth];
+			branches.push(BranchStep { hash: keccak(node.as_raw()), prefix: nibbles[..depth].to_vec(), depth, nibble });
+
+			let child = node.at(nibble as usize).map_err(|_| ProofError::BadNode)?;
+			follow(proof, child, nibbles, depth + 1, used, branches)
+		}
+		2 => {
+			let path_rlp = node.at(0).map_err(|_| ProofError::BadNode)?;
+			let path_bytes = path_rlp.data().map_err(|_| ProofError::BadNode)?;
+			let (path, is_leaf) = hex_prefix_decode(path_bytes)?;
+
+			if nibbles[depth..].len() < path.len() || nibbles[depth..depth + path.len()] != path[..] {
+				return Ok(BoundaryResult::Excluded);
+			}
+
+			let new_depth = depth + path.len();
+			let value_rlp = node.at(1).map_err(|_| ProofError::BadNode)?;
+
+			if is_leaf {
+				if new_depth != nibbles.len() {
+					return Ok(BoundaryResult::Excluded);
+				}
+				Ok(BoundaryResult::Included(rlp_to_h256(&value_rlp)?))
+			} else {
+				follow(proof, value_rlp, nibbles, new_depth, used, branches)
+			}
+		}
+		_ => Err(ProofError::BadNode),
+	}
+}
+
+fn follow(
+	proof: &[Bytes],
+	child: Rlp,
+	nibbles: &[u8],
+	depth: usize,
+	used: &mut [bool],
+	branches: &mut Vec<BranchStep>,
+) -> Result<BoundaryResult, ProofError> {
+	match decode_child(&child)? {
+		Child::Empty => Ok(BoundaryResult::Excluded),
+		Child::Hash(hash) => walk(proof, hash, nibbles, depth, used, branches),
+		Child::Inline(rlp) => walk_node(proof, rlp, nibbles, depth, used, branches),
+	}
+}
+
+// Build the RLP encoding of the (sub)trie containing exactly `entries`,
+// whose keys are given as their remaining nibble suffix from this point.
+fn build_subtrie(entries: &[(Vec<u8>, H256)]) -> Vec<u8> {
+	if entries.len() == 1 {
+		let (suffix, value) = &entries[0];
+		let path = hex_prefix_encode(suffix, true);
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&path);
+		stream.append(&trimmed_value(value).to_vec());
+		return stream.out().to_vec();
+	}
+
+	let cp = entries[1..].iter()
+		.map(|(suffix, _)| common_prefix_len(&entries[0].0, suffix))
+		.min()
+		.expect("entries.len() > 1 checked above, so entries[1..] is non-empty; qed");
+
+	if cp > 0 {
+		let stripped: Vec<(Vec<u8>, H256)> = entries.iter()
+			.map(|(suffix, value)| (suffix[cp..].to_vec(), *value))
+			.collect();
+		let child_rlp = build_subtrie(&stripped);
+
+		let path = hex_prefix_encode(&entries[0].0[..cp], false);
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&path);
+		append_child_ref(&mut stream, &child_rlp);
+		return stream.out().to_vec();
+	}
+
+	let mut buckets: Vec<Vec<(Vec<u8>, H256)>> = vec![Vec::new(); 16];
+	for (suffix, value) in entries {
+		buckets[suffix[0] as usize].push((suffix[1..].to_vec(), *value));
+	}
+
+	let mut stream = RlpStream::new_list(17);
+	for bucket in &buckets {
+		if bucket.is_empty() {
+			stream.append_empty_data();
+		} else {
+			append_child_ref(&mut stream, &build_subtrie(bucket));
+		}
+	}
+	stream.append_empty_data(); // value slot: unreachable for fixed-length keys
+	stream.out().to_vec()
+}
+
+impl Response {
+	/// Self-verify this range proof against a known storage root and the
+	/// originating request's bounds.
+	///
+	/// Implements the four checks the format is designed around:
+	///
+	/// 1. The left boundary proof authenticates either the first returned
+	///    entry's inclusion, or the exclusion of `start_hash` (empty range).
+	/// 2. A partial trie is rebuilt from the returned `entries` alone.
+	/// 3. The right boundary proof authenticates either the last returned
+	///    entry's inclusion, or the exclusion of `limit_hash` (empty range).
+	/// 4. Every subtree hanging off either boundary path that isn't itself
+	///    walked by a boundary proof -- the span strictly between the two
+	///    paths at their shared ancestor branch, the unwalked siblings below
+	///    the left path, and the unwalked siblings below the right path --
+	///    is recomputed from `entries` and checked against the hash
+	///    referenced by its parent branch, confirming no leaf in that span
+	///    was altered or omitted.
+	///
+	/// Leaves between `start_hash` and the first returned entry, or between
+	/// the last returned entry and `limit_hash`, are not checked for omission.
+	pub fn verify(&self, storage_root: H256, start_hash: H256, limit_hash: H256) -> Result<(), ProofError> {
+		if start_hash > limit_hash {
+			return Err(ProofError::OutOfOrder);
+		}
+
+		for entry in &self.entries {
+			if entry.key_hash < start_hash || entry.key_hash > limit_hash {
+				return Err(ProofError::OutOfOrder);
+			}
+		}
+		for pair in self.entries.windows(2) {
+			if pair[0].key_hash >= pair[1].key_hash {
+				return Err(ProofError::OutOfOrder);
+			}
+		}
+
+		let left_target = self.entries.first().map(|e| e.key_hash).unwrap_or(start_hash);
+		let (left_result, left_branches) = walk_boundary(&self.left_proof, storage_root, &key_nibbles(&left_target))?;
+
+		match (&left_result, self.entries.first()) {
+			(BoundaryResult::Included(value), Some(entry)) if *value == entry.value => {}
+			(BoundaryResult::Excluded, None) => {}
+			_ => return Err(ProofError::BadLeftBoundary),
+		}
+
+		let right_target = self.entries.last().map(|e| e.key_hash).unwrap_or(limit_hash);
+		let (right_result, right_branches) = walk_boundary(&self.right_proof, storage_root, &key_nibbles(&right_target))?;
+
+		match (&right_result, self.entries.last()) {
+			(BoundaryResult::Included(value), Some(entry)) if *value == entry.value => {}
+			(BoundaryResult::Excluded, None) => {}
+			_ => return Err(ProofError::BadRightBoundary),
+		}
+
+		self.check_range_gap(&left_branches, &right_branches)
+	}
+
+	// Where the left and right boundary proofs share an ancestor branch node,
+	// rebuild every subtree strictly between the two taken nibbles from
+	// `entries` and check it against the branch's own child hash. Below that
+	// point of divergence, the left path's own subtree may still have
+	// sibling children beyond the nibble it took (closer to the range's
+	// upper end), and likewise the right path's subtree may have sibling
+	// children before the nibble it took (closer to the range's lower end) --
+	// both must also be fully accounted for by `entries`, or a leaf in
+	// between could have been silently dropped.
+	fn check_range_gap(&self, left_branches: &[BranchStep], right_branches: &[BranchStep]) -> Result<(), ProofError> {
+		let mut i = 0;
+		while i < left_branches.len() && i < right_branches.len()
+			&& left_branches[i].hash == right_branches[i].hash
+			&& left_branches[i].depth == right_branches[i].depth
+			&& left_branches[i].nibble == right_branches[i].nibble
+		{
+			i += 1;
+		}
+
+		let shares_divergence_branch = i < left_branches.len() && i < right_branches.len()
+			&& left_branches[i].hash == right_branches[i].hash
+			&& left_branches[i].depth == right_branches[i].depth;
+
+		let rest_from = if shares_divergence_branch {
+			let left_nibble = left_branches[i].nibble;
+			let right_nibble = right_branches[i].nibble;
+			if left_nibble >= right_nibble {
+				return Err(ProofError::RangeGap);
+			}
+			self.check_branch_span(&left_branches[i], left_nibble + 1, right_nibble)?;
+			i + 1
+		} else {
+			i
+		};
+
+		// The remainder of the left path, below the divergence point: every
+		// child past the one the left boundary actually took is still
+		// inside the proven range (it diverged from the right path higher
+		// up, so it can't cross `limit_hash`) and must be covered by `entries`.
+		for step in left_branches.get(rest_from..).unwrap_or(&[]) {
+			self.check_branch_span(step, step.nibble + 1, 16)?;
+		}
+
+		// Symmetrically, the remainder of the right path: every child
+		// before the one the right boundary took must be covered.
+		for step in right_branches.get(rest_from..).unwrap_or(&[]) {
+			self.check_branch_span(step, 0, step.nibble)?;
+		}
+
+		Ok(())
+	}
+
+	// Check that every child in `lo..hi` of the branch node `step` refers to
+	// (either by hash or inline) exactly the subtrie that `entries` would
+	// produce for that child, so that no leaf in that span was altered or
+	// omitted from the response.
+	fn check_branch_span(&self, step: &BranchStep, lo: u8, hi: u8) -> Result<(), ProofError> {
+		if lo >= hi {
+			return Ok(());
+		}
+
+		let node = self.find_branch(step.hash).ok_or(ProofError::MissingNode(step.hash))?;
+		let node = Rlp::new(&node);
+
+		for nibble in lo..hi {
+			let child = node.at(nibble as usize).map_err(|_| ProofError::BadNode)?;
+			let spanned: Vec<(Vec<u8>, H256)> = self.entries.iter()
+				.filter(|e| {
+					let key = key_nibbles(&e.key_hash);
+					key[..step.depth] == step.prefix[..] && key[step.depth] == nibble
+				})
+				.map(|e| (key_nibbles(&e.key_hash)[step.depth + 1..].to_vec(), e.value))
+				.collect();
+
+			match (decode_child(&child)?, spanned.is_empty()) {
+				(Child::Empty, true) => {}
+				(Child::Empty, false) => return Err(ProofError::RangeGap),
+				(_, true) => return Err(ProofError::RangeGap),
+				(Child::Hash(hash), false) => {
+					if keccak(build_subtrie(&spanned)) != hash {
+						return Err(ProofError::RangeGap);
+					}
+				}
+				(Child::Inline(rlp), false) => {
+					if rlp.as_raw() != build_subtrie(&spanned).as_slice() {
+						return Err(ProofError::RangeGap);
+					}
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	fn find_branch(&self, hash: H256) -> Option<Bytes> {
+		self.left_proof.iter().chain(self.right_proof.iter())
+			.find(|node| keccak(*node) == hash)
+			.cloned()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{Response, Entry, ProofError, keccak};
+	use ethereum_types::H256;
+	use rlp::RlpStream;
+
+	// Test-only mirror of `hex_prefix_decode`/`hex_prefix_encode`, used to
+	// build proof fixtures independently of the code under test.
+	fn hex_prefix_encode(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+		let odd = nibbles.len() % 2 == 1;
+		let mut out = Vec::with_capacity(nibbles.len() / 2 + 1);
+
+		let mut first = if is_leaf { 0x20 } else { 0x00 };
+		let mut idx = 0;
+		if odd {
+			first |= 0x10 | nibbles[0];
+			idx = 1;
+		}
+		out.push(first);
+
+		while idx < nibbles.len() {
+			out.push((nibbles[idx] << 4) | nibbles[idx + 1]);
+			idx += 2;
+		}
+
+		out
+	}
+
+	fn trimmed(value: &H256) -> Vec<u8> {
+		let bytes = value.as_bytes();
+		match bytes.iter().position(|&b| b != 0) {
+			Some(i) => bytes[i..].to_vec(),
+			None => Vec::new(),
+		}
+	}
+
+	fn leaf_node(suffix: &[u8], value: H256) -> Vec<u8> {
+		let mut stream = RlpStream::new_list(2);
+		stream.append(&hex_prefix_encode(suffix, true));
+		stream.append(&trimmed(&value));
+		stream.out().to_vec()
+	}
+
+	fn branch_node(children: &[Option<Vec<u8>>]) -> Vec<u8> {
+		assert_eq!(children.len(), 16);
+		let mut stream = RlpStream::new_list(17);
+		for child in children {
+			match child {
+				Some(node) => append_child(&mut stream, node),
+				None => { stream.append_empty_data(); }
+			}
+		}
+		stream.append_empty_data();
+		stream.out().to_vec()
+	}
+
+	fn append_child(stream: &mut RlpStream, child: &[u8]) {
+		if child.len() < 32 {
+			stream.append_raw(child, 1);
+		} else {
+			stream.append(&keccak(child));
+		}
+	}
+
+	fn no_children() -> Vec<Option<Vec<u8>>> {
+		vec![None; 16]
+	}
+
+	// A 32-byte key hash with nibbles `[hi, lo, 0, 0, ..., 0]` (64 nibbles).
+	fn test_key(hi: u8, lo: u8) -> H256 {
+		let mut bytes = [0u8; 32];
+		bytes[0] = (hi << 4) | lo;
+		H256::from(bytes)
+	}
+
+	// A two-level trie shared by the range tests below:
+	//
+	//           root (depth 0)
+	//          /              \
+	//    nibble 5          nibble 9
+	//        |                 |
+	//     branch1          leaf_right (suffix: 63 zeros)
+	//    /        \
+	// nibble 1   nibble 11
+	//    |            |
+	// leaf_left   leaf_mid   (suffix: 62 zeros)
+	//
+	// `leaf_mid`'s key (nibbles [5, 11, 0, ...]) sits strictly between
+	// `leaf_left`'s key ([5, 1, 0, ...]) and `leaf_right`'s key ([9, 0, ...]),
+	// but diverges from `leaf_left` one level *below* the branch (root) where
+	// the left and right boundary paths actually diverge -- exactly the case
+	// `check_range_gap` must not miss.
+	struct Fixture {
+		root_hash: H256,
+		left_proof: Vec<Vec<u8>>,
+		right_proof: Vec<Vec<u8>>,
+		left_key: H256,
+		mid_key: H256,
+		right_key: H256,
+		left_value: H256,
+		mid_value: H256,
+		right_value: H256,
+	}
+
+	fn build_fixture() -> Fixture {
+		let left_key = test_key(5, 1);
+		let mid_key = test_key(5, 11);
+		let right_key = test_key(9, 0);
+
+		let left_value = H256::from_low_u64_be(10);
+		let mid_value = H256::from_low_u64_be(20);
+		let right_value = H256::from_low_u64_be(30);
+
+		let leaf_left = leaf_node(&[0u8; 62], left_value);
+		let leaf_mid = leaf_node(&[0u8; 62], mid_value);
+		let leaf_right = leaf_node(&[0u8; 63], right_value);
+
+		let mut branch1_children = no_children();
+		branch1_children[1] = Some(leaf_left.clone());
+		branch1_children[11] = Some(leaf_mid.clone());
+		let branch1 = branch_node(&branch1_children);
+		assert!(branch1.len() >= 32, "fixture assumption: branch1 is hash-referenced");
+
+		let mut root_children = no_children();
+		root_children[5] = Some(branch1.clone());
+		root_children[9] = Some(leaf_right.clone());
+		let root = branch_node(&root_children);
+		let root_hash = keccak(&root);
+
+		Fixture {
+			left_proof: vec![root.clone(), branch1, leaf_left],
+			right_proof: vec![root, leaf_right],
+			root_hash,
+			left_key,
+			mid_key,
+			right_key,
+			left_value,
+			mid_value,
+			right_value,
+		}
+	}
+
+	#[test]
+	fn verify_accepts_honest_multi_entry_range() {
+		let f = build_fixture();
+		let entries = vec![
+			Entry { key_hash: f.left_key, value: f.left_value },
+			Entry { key_hash: f.mid_key, value: f.mid_value },
+			Entry { key_hash: f.right_key, value: f.right_value },
+		];
+
+		let response = Response {
+			entries,
+			left_proof: f.left_proof,
+			right_proof: f.right_proof,
+			next_hash: f.right_key,
+		};
+
+		assert_eq!(response.verify(f.root_hash, f.left_key, f.right_key), Ok(()));
+	}
+
+	#[test]
+	fn verify_rejects_entry_omitted_from_divergent_subtree() {
+		let f = build_fixture();
+		// `mid_key` is dropped even though both boundary proofs still
+		// honestly authenticate `left_key` and `right_key`: it shares a
+		// branch with `left_key` one level below where the two boundary
+		// paths actually diverge from each other, so a check that only
+		// looks at the shared divergence branch itself would miss it.
+		let entries = vec![
+			Entry { key_hash: f.left_key, value: f.left_value },
+			Entry { key_hash: f.right_key, value: f.right_value },
+		];
+
+		let response = Response {
+			entries,
+			left_proof: f.left_proof,
+			right_proof: f.right_proof,
+			next_hash: f.right_key,
+		};
+
+		assert_eq!(response.verify(f.root_hash, f.left_key, f.right_key), Err(ProofError::RangeGap));
+	}
+
+	#[test]
+	fn verify_accepts_empty_range() {
+		let root = branch_node(&no_children());
+		let root_hash = keccak(&root);
+
+		let start_hash = test_key(5, 1);
+		let limit_hash = test_key(9, 0);
+
+		let response = Response {
+			entries: Vec::new(),
+			left_proof: vec![root.clone()],
+			right_proof: vec![root],
+			next_hash: limit_hash,
+		};
+
+		assert_eq!(response.verify(root_hash, start_hash, limit_hash), Ok(()));
+	}
+}